@@ -0,0 +1,132 @@
+// Parsec Cloud (https://parsec.cloud) Copyright (c) BUSL-1.1 2016-present Scille SAS
+
+//! Dynamic re-application of the prevent-sync pattern.
+//!
+//! Entries whose name matches the prevent-sync pattern are "confined": kept
+//! local-only and never synced to the server (see `confinement_point` in
+//! `entry_transactions`). The pattern itself (a regex, e.g. `\.tmp$`, `^~\$`) can change
+//! at runtime — the user edits it, or it comes from an updated org policy —
+//! so every local folder manifest's `local_confinement_points` and
+//! `remote_confinement_points` must be recomputed against the new pattern
+//! instead of only being set once at manifest-creation time. This mirrors
+//! the `force_apply_pattern` merge step run when a folder manifest is synced.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use regex::Regex;
+
+use libparsec_types::prelude::*;
+
+use super::entry_transactions::{get_child_manifest, resolve_path};
+use super::error_conversions::{impl_from_entry_id_from_path_error, impl_from_get_child_manifest_error};
+use super::WorkspaceOps;
+
+/// A compiled prevent-sync pattern, cheap to clone and share since recompute
+/// happens on every local manifest each time the pattern changes.
+#[derive(Debug, Clone)]
+pub(super) struct PreventSyncPattern(Arc<Regex>);
+
+impl PreventSyncPattern {
+    pub(super) fn from_regex(regex: Regex) -> Self {
+        Self(Arc::new(regex))
+    }
+
+    fn matches(&self, name: &EntryName) -> bool {
+        self.0.is_match(name.as_ref())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RecomputeConfinementError {
+    #[error("Path doesn't exist")]
+    NotFound,
+    #[error("Cannot reach the server")]
+    Offline,
+    #[error("Not allowed to access this realm")]
+    NotAllowed,
+    #[error("Our clock ({client_timestamp}) and the server's one ({server_timestamp}) are too far apart")]
+    BadTimestamp {
+        server_timestamp: DateTime,
+        client_timestamp: DateTime,
+        ballpark_client_early_offset: f64,
+        ballpark_client_late_offset: f64,
+    },
+    #[error(transparent)]
+    Internal(#[from] anyhow::Error),
+}
+
+impl_from_entry_id_from_path_error!(RecomputeConfinementError);
+impl_from_get_child_manifest_error!(RecomputeConfinementError);
+
+/// Recompute `local_confinement_points` and `remote_confinement_points`
+/// against `new_pattern` for `root` and every folder manifest below it,
+/// persisting (and marking `need_sync`) only the ones whose sets actually
+/// changed.
+///
+/// The walk goes through `get_child_manifest`, so a child manifest that isn't
+/// in local storage yet is fetched from the server (and stored) on the way,
+/// which is why this can fail with `Offline`.
+pub(super) async fn reapply_prevent_sync_pattern(
+    ops: &WorkspaceOps,
+    root: &FsPath,
+    new_pattern: &PreventSyncPattern,
+) -> Result<usize, RecomputeConfinementError> {
+    let resolution = resolve_path(ops, root).await?;
+    let mut updated = 0;
+    reapply_rec(ops, resolution.entry_id, new_pattern, &mut updated).await?;
+    Ok(updated)
+}
+
+#[async_recursion::async_recursion]
+async fn reapply_rec(
+    ops: &WorkspaceOps,
+    entry_id: VlobID,
+    new_pattern: &PreventSyncPattern,
+    updated: &mut usize,
+) -> Result<(), RecomputeConfinementError> {
+    let ArcLocalChildManifest::Folder(manifest) = get_child_manifest(ops, entry_id).await? else {
+        // Files have no children and carry no confinement set of their own.
+        return Ok(());
+    };
+
+    // Confined on the local side: entries visible in our own `children`
+    // view (whether synced yet or not) whose name now matches the pattern.
+    let recomputed_local: HashSet<VlobID> = manifest
+        .children
+        .iter()
+        .filter_map(|(name, id)| new_pattern.matches(name).then_some(*id))
+        .collect();
+    // Confined on the remote side: entries already present in the last
+    // synced (`base`) version whose name now matches the pattern, kept out
+    // of sync even though the server already has a copy.
+    let recomputed_remote: HashSet<VlobID> = manifest
+        .base
+        .children
+        .iter()
+        .filter_map(|(name, id)| new_pattern.matches(name).then_some(*id))
+        .collect();
+
+    let local_changed = recomputed_local != manifest.local_confinement_points;
+    let remote_changed = recomputed_remote != manifest.remote_confinement_points;
+
+    if local_changed || remote_changed {
+        let mut recomputed_manifest = (*manifest).clone();
+        recomputed_manifest.local_confinement_points = recomputed_local;
+        recomputed_manifest.remote_confinement_points = recomputed_remote;
+        recomputed_manifest.need_sync = true;
+
+        let (updater, _) = ops.data_storage.for_update_child_manifest(entry_id).await?;
+        updater
+            .set_folder_manifest(Arc::new(recomputed_manifest))
+            .await?;
+        ops.child_manifest_cache.invalidate(entry_id);
+        *updated += 1;
+    }
+
+    for child_id in manifest.children.values() {
+        reapply_rec(ops, *child_id, new_pattern, updated).await?;
+    }
+
+    Ok(())
+}
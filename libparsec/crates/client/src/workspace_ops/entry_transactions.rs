@@ -7,6 +7,7 @@ use libparsec_types::prelude::*;
 
 use super::{
     fetch::{fetch_remote_child_manifest, FetchRemoteManifestError},
+    merge,
     WorkspaceOps,
 };
 use crate::certificates_ops::{InvalidCertificateError, InvalidManifestError};
@@ -34,7 +35,21 @@ pub enum GetChildManifestError {
     Internal(#[from] anyhow::Error),
 }
 
-async fn get_child_manifest(
+pub(super) async fn get_child_manifest(
+    ops: &WorkspaceOps,
+    entry_id: VlobID,
+) -> Result<ArcLocalChildManifest, GetChildManifestError> {
+    // Several callers (a directory listing, a concurrent sync, ...) may ask
+    // for the same manifest at once, and the same id is often re-requested
+    // moments later by a sibling path walk. `child_manifest_cache` coalesces
+    // concurrent fetches onto a single storage/server round-trip and keeps
+    // the result warm for later callers.
+    ops.child_manifest_cache
+        .get_or_fetch(entry_id, || do_get_child_manifest(ops, entry_id))
+        .await
+}
+
+async fn do_get_child_manifest(
     ops: &WorkspaceOps,
     entry_id: VlobID,
 ) -> Result<ArcLocalChildManifest, GetChildManifestError> {
@@ -67,9 +82,31 @@ async fn get_child_manifest(
                 ops.data_storage.for_update_child_manifest(entry_id).await?;
             match expect_missing_manifest {
                 // Plot twist: a concurrent operation has inserted the manifest in the storage !
-                // TODO: we could be trying to update the existing data with the brand new one
-                // however this would most likely do nothing (as the concurrent version must based
-                // on very recent data)
+                // If both sides are folders, three-way merge their children (the concurrent
+                // insert race: two fetches for the same id racing each other can't tell which
+                // one is "more local", so the already-stored manifest plays `local` and the one
+                // we just fetched plays `remote`, see `merge::merge_manifests`).
+                Some(ArcLocalChildManifest::Folder(local_manifest)) => {
+                    match remote_manifest {
+                        ChildManifest::Folder(remote_manifest) => {
+                            let merged = merge::merge_manifests(
+                                &ops.device.device_id,
+                                DateTime::now(),
+                                None,
+                                Some(&local_manifest),
+                                &remote_manifest,
+                            );
+                            let manifest = Arc::new(merged.manifest);
+                            updater.set_folder_manifest(manifest.clone()).await?;
+                            ops.child_manifest_cache.invalidate(entry_id);
+                            Ok(ArcLocalChildManifest::Folder(manifest))
+                        }
+                        // A folder got concurrently replaced by a file (or vice-versa): this
+                        // can only happen through a name reuse after a delete+recreate, the
+                        // already-stored manifest is as valid a view as any, keep it.
+                        ChildManifest::File(_) => Ok(ArcLocalChildManifest::Folder(local_manifest)),
+                    }
+                }
                 Some(local_manifest) => Ok(local_manifest),
 
                 // As expected the storage didn't contain the manifest, it's up to us to store it then !
@@ -81,12 +118,20 @@ async fn get_child_manifest(
                             updater
                                 .set_file_manifest(manifest.clone(), false, [].into_iter())
                                 .await?;
+                            ops.child_manifest_cache.invalidate(entry_id);
                             ArcLocalChildManifest::File(manifest)
                         }
                         ChildManifest::Folder(remote_manifest) => {
-                            let manifest =
-                                Arc::new(LocalFolderManifest::from_remote(remote_manifest, None));
+                            let merged = merge::merge_manifests(
+                                &ops.device.device_id,
+                                DateTime::now(),
+                                None,
+                                None,
+                                &remote_manifest,
+                            );
+                            let manifest = Arc::new(merged.manifest);
                             updater.set_folder_manifest(manifest.clone()).await?;
+                            ops.child_manifest_cache.invalidate(entry_id);
                             ArcLocalChildManifest::Folder(manifest)
                         }
                     };
@@ -128,14 +173,14 @@ pub enum EntryInfo {
     },
 }
 
-struct FsPathResolution {
-    entry_id: VlobID,
+pub(super) struct FsPathResolution {
+    pub(super) entry_id: VlobID,
     /// The confinement point corresponds to the entry id of the folderish manifest
     /// (i.e. file or workspace manifest) that contains a child with a confined name
     /// in the corresponding path.
     ///
     /// If the entry is not confined, the confinement point is `None`.
-    confinement_point: Option<VlobID>,
+    pub(super) confinement_point: Option<VlobID>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -161,7 +206,7 @@ pub enum EntryIDFromPathError {
     Internal(#[from] anyhow::Error),
 }
 
-async fn resolve_path(
+pub(super) async fn resolve_path(
     ops: &WorkspaceOps,
     path: &FsPath,
 ) -> Result<FsPathResolution, EntryIDFromPathError> {
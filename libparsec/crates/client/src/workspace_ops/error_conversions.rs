@@ -0,0 +1,75 @@
+// Parsec Cloud (https://parsec.cloud) Copyright (c) BUSL-1.1 2016-present Scille SAS
+
+//! Shared `From` conversions for the `Offline`/`NotFound`/`NotAllowed`/
+//! `BadTimestamp`/`Internal` error shape that `entry_transactions.rs`
+//! established and that every other `workspace_ops` submodule's own error
+//! type needs a conversion from, so each of them doesn't paste the same
+//! field-by-field mapping.
+
+use super::entry_transactions::{EntryIDFromPathError, GetChildManifestError};
+
+/// Implement `From<EntryIDFromPathError> for $target`, for a `$target` that
+/// has `NotFound`/`Offline`/`NotAllowed`/`BadTimestamp { .. }`/`Internal`
+/// variants matching `entry_transactions::GetChildManifestError`'s shape
+/// (certificate/manifest validity errors are folded into `Internal`, since
+/// by the time a path is being resolved those have already been reported
+/// further down the stack).
+macro_rules! impl_from_entry_id_from_path_error {
+    ($target:ty) => {
+        impl From<EntryIDFromPathError> for $target {
+            fn from(err: EntryIDFromPathError) -> Self {
+                match err {
+                    EntryIDFromPathError::NotFound => Self::NotFound,
+                    EntryIDFromPathError::Offline => Self::Offline,
+                    EntryIDFromPathError::NotAllowed => Self::NotAllowed,
+                    EntryIDFromPathError::InvalidCertificate(x) => Self::Internal(x.into()),
+                    EntryIDFromPathError::InvalidManifest(x) => Self::Internal(x.into()),
+                    EntryIDFromPathError::BadTimestamp {
+                        server_timestamp,
+                        client_timestamp,
+                        ballpark_client_early_offset,
+                        ballpark_client_late_offset,
+                    } => Self::BadTimestamp {
+                        server_timestamp,
+                        client_timestamp,
+                        ballpark_client_early_offset,
+                        ballpark_client_late_offset,
+                    },
+                    EntryIDFromPathError::Internal(x) => Self::Internal(x),
+                }
+            }
+        }
+    };
+}
+pub(super) use impl_from_entry_id_from_path_error;
+
+/// Implement `From<GetChildManifestError> for $target`, same rationale and
+/// shape requirements as [`impl_from_entry_id_from_path_error`].
+macro_rules! impl_from_get_child_manifest_error {
+    ($target:ty) => {
+        impl From<GetChildManifestError> for $target {
+            fn from(err: GetChildManifestError) -> Self {
+                match err {
+                    GetChildManifestError::Offline => Self::Offline,
+                    GetChildManifestError::NotFound => Self::NotFound,
+                    GetChildManifestError::NotAllowed => Self::NotAllowed,
+                    GetChildManifestError::InvalidCertificate(x) => Self::Internal(x.into()),
+                    GetChildManifestError::InvalidManifest(x) => Self::Internal(x.into()),
+                    GetChildManifestError::BadTimestamp {
+                        server_timestamp,
+                        client_timestamp,
+                        ballpark_client_early_offset,
+                        ballpark_client_late_offset,
+                    } => Self::BadTimestamp {
+                        server_timestamp,
+                        client_timestamp,
+                        ballpark_client_early_offset,
+                        ballpark_client_late_offset,
+                    },
+                    GetChildManifestError::Internal(x) => Self::Internal(x),
+                }
+            }
+        }
+    };
+}
+pub(super) use impl_from_get_child_manifest_error;
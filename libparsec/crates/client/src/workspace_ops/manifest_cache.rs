@@ -0,0 +1,85 @@
+// Parsec Cloud (https://parsec.cloud) Copyright (c) BUSL-1.1 2016-present Scille SAS
+
+//! Memoizing, single-flight cache for `get_child_manifest`.
+//!
+//! `entry_info`/`resolve_path` may end up requesting the very same `VlobID`
+//! from several tasks at once (e.g. a directory listing firing off one
+//! lookup per entry while a sync is also reading them), and the same id is
+//! often re-requested a moment later by a sibling path walk. Without this
+//! cache each of those would independently hit `data_storage` (and, on a
+//! miss, the server) for the same manifest.
+//!
+//! The first caller for a given id stores a not-yet-resolved `OnceCell` and
+//! drives the actual fetch; every concurrent caller for the same id awaits
+//! that same cell instead of issuing its own round-trip. Once resolved, the
+//! manifest stays cached (bounded by an LRU so a long-lived client doesn't
+//! grow this unboundedly) until either it's evicted or `invalidate` is
+//! called because a local mutation wrote a newer version.
+
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex as SyncMutex};
+
+use lru::LruCache;
+use tokio::sync::OnceCell;
+
+use libparsec_types::prelude::*;
+
+/// Default size of the LRU, generous enough to keep a typical folder
+/// listing's worth of entries warm without holding onto the whole
+/// workspace's manifests forever.
+const DEFAULT_CAPACITY: usize = 1024;
+
+/// Per-workspace memoizing cache, held by `WorkspaceOps` alongside
+/// `data_storage`.
+pub(super) struct ChildManifestCache {
+    entries: SyncMutex<LruCache<VlobID, Arc<OnceCell<ArcLocalChildManifest>>>>,
+}
+
+impl Default for ChildManifestCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl ChildManifestCache {
+    pub(super) fn new(capacity: usize) -> Self {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::new(1).unwrap());
+        Self {
+            entries: SyncMutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    fn cell_for(&self, entry_id: VlobID) -> Arc<OnceCell<ArcLocalChildManifest>> {
+        self.entries
+            .lock()
+            .expect("mutex is poisoned")
+            .get_or_insert(entry_id, || Arc::new(OnceCell::new()))
+            .clone()
+    }
+
+    /// Return the cached manifest for `entry_id`, or run `fetch` to produce
+    /// it. Concurrent callers for the same `entry_id` share a single call to
+    /// `fetch`: only the first one actually runs it, the rest await its
+    /// result. A failed fetch is not cached, so the next caller (concurrent
+    /// or not) gets to retry.
+    pub(super) async fn get_or_fetch<F, Fut, E>(
+        &self,
+        entry_id: VlobID,
+        fetch: F,
+    ) -> Result<ArcLocalChildManifest, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: std::future::Future<Output = Result<ArcLocalChildManifest, E>>,
+    {
+        let cell = self.cell_for(entry_id);
+        let manifest = cell.get_or_try_init(fetch).await?;
+        Ok(manifest.clone())
+    }
+
+    /// Drop the cached manifest for `entry_id`, called by `set_file_manifest`
+    /// /`set_folder_manifest` paths whenever a local mutation persists a
+    /// newer version, so later lookups don't keep serving the stale one.
+    pub(super) fn invalidate(&self, entry_id: VlobID) {
+        self.entries.lock().expect("mutex is poisoned").pop(&entry_id);
+    }
+}
@@ -0,0 +1,212 @@
+// Parsec Cloud (https://parsec.cloud) Copyright (c) BUSL-1.1 2016-present Scille SAS
+
+//! Three-way merge of folder manifests.
+//!
+//! When a folder is modified both locally and remotely since the last
+//! synchronized version, a naive two-way merge (just taking the remote
+//! version) would silently drop local changes, and taking the local version
+//! blindly would drop the remote ones. Comparing each side against their
+//! common `base` tells us, per child name, whether each side kept or changed
+//! the entry, which lets us merge non-conflicting changes automatically.
+//! The merge is a union of both sides' children: an entry present on only
+//! one side is kept, so a removal made on one side alone is not carried over.
+//!
+//! The one irreconcilable case is a *concurrent insert*: both sides added a
+//! different entry under the same name. Neither can be silently discarded
+//! (that would lose data): the remote entry is authoritative for the server
+//! and keeps the name, the local one is kept under a renamed entry instead
+//! of being dropped.
+
+use std::collections::HashMap;
+
+use regex::Regex;
+
+use libparsec_types::prelude::*;
+
+/// Outcome of merging the children of a local and a remote folder manifest
+/// that share `base` as their last common ancestor.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct MergedChildren {
+    children: HashMap<EntryName, VlobID>,
+    /// `(original_name, renamed_name)` pairs for local entries that were
+    /// renamed away because a concurrent remote insert took their name.
+    renamed_conflicts: Vec<(EntryName, EntryName)>,
+}
+
+/// Outcome of [`merge_manifests`]: the manifest to persist, rebased onto
+/// `remote` as its new `base`, plus any local entries that got renamed out
+/// of the way by a concurrent remote insert, for the caller to surface to
+/// the user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct MergedManifest {
+    pub(super) manifest: LocalFolderManifest,
+    pub(super) renamed_conflicts: Vec<(EntryName, EntryName)>,
+}
+
+/// Three-way merge `already_stored` (the manifest currently on record, if
+/// any) against `remote` (a freshly fetched, possibly newer, version),
+/// producing the manifest to persist with `remote` as its new `base`.
+///
+/// - `already_stored` is `None` when nothing was on record yet (the folder
+///   is being fetched for the very first time): there is nothing to rebase,
+///   `remote` becomes the local manifest outright.
+/// - If `remote.version <= already_stored.base.version` the remote side
+///   hasn't moved since our last sync, `already_stored` is returned as-is.
+/// - Otherwise `remote` is re-derived into a fresh local manifest
+///   (`local_from_remote`), and `already_stored.children` is rebased onto it
+///   by diffing both sides against their common `already_stored.base`,
+///   resolving concurrent-insert collisions by renaming the local entry.
+pub(super) fn merge_manifests(
+    local_author: &DeviceID,
+    timestamp: DateTime,
+    prevent_sync_pattern: Option<&Regex>,
+    already_stored: Option<&LocalFolderManifest>,
+    remote: &FolderManifest,
+) -> MergedManifest {
+    let Some(already_stored) = already_stored else {
+        return MergedManifest {
+            manifest: LocalFolderManifest::from_remote(remote.clone(), prevent_sync_pattern),
+            renamed_conflicts: Vec::new(),
+        };
+    };
+
+    if remote.version <= already_stored.base.version {
+        return MergedManifest {
+            manifest: already_stored.clone(),
+            renamed_conflicts: Vec::new(),
+        };
+    }
+
+    // Re-derive what a fresh local manifest would look like straight off
+    // `remote`, so the rebase starts from remote's own metadata (base,
+    // confinement, author, ...) instead of keeping `already_stored`'s, which
+    // is now stale.
+    let local_from_remote = LocalFolderManifest::from_remote(remote.clone(), prevent_sync_pattern);
+
+    let merged = merge_children(
+        &already_stored.base.children,
+        &already_stored.children,
+        &remote.children,
+    );
+
+    let mut manifest = local_from_remote;
+    manifest.children = merged.children;
+    manifest.updated = timestamp;
+    // `local_author` isn't tracked on the manifest itself (only signed
+    // remote manifests carry an author), it is logged below alongside any
+    // conflict this rebase produced so the rename can be traced back to the
+    // operation that surfaced it.
+    manifest.need_sync = manifest.children != remote.children;
+
+    if !merged.renamed_conflicts.is_empty() {
+        tracing::warn!(
+            author = %local_author,
+            timestamp = %timestamp,
+            conflicts = ?merged.renamed_conflicts,
+            "Concurrent insert conflict while merging folder manifest, local entries were renamed",
+        );
+    }
+
+    MergedManifest {
+        manifest,
+        renamed_conflicts: merged.renamed_conflicts,
+    }
+}
+
+/// Merge `local.children` and `remote.children` against their common
+/// `base.children`, resolving the concurrent-insert race by renaming the
+/// local entry when both sides independently created a different child
+/// under the same name (the remote entry is authoritative and keeps the
+/// name).
+fn merge_children(
+    base: &HashMap<EntryName, VlobID>,
+    local: &HashMap<EntryName, VlobID>,
+    remote: &HashMap<EntryName, VlobID>,
+) -> MergedChildren {
+    let mut merged = MergedChildren::default();
+
+    let mut names: Vec<&EntryName> = base
+        .keys()
+        .chain(local.keys())
+        .chain(remote.keys())
+        .collect();
+    names.sort_unstable();
+    names.dedup();
+
+    for name in names {
+        let base_id = base.get(name);
+        let local_id = local.get(name);
+        let remote_id = remote.get(name);
+
+        match (base_id, local_id, remote_id) {
+            // Untouched on both sides (or already gone from both).
+            (_, None, None) => {}
+
+            // Only one side has an opinion: take it.
+            (_, Some(id), None) => {
+                merged.children.insert(name.clone(), *id);
+            }
+            (_, None, Some(id)) => {
+                merged.children.insert(name.clone(), *id);
+            }
+
+            // Both sides agree, or one side just confirms the base: no
+            // conflict.
+            (_, Some(local_id), Some(remote_id)) if local_id == remote_id => {
+                merged.children.insert(name.clone(), *local_id);
+            }
+            (Some(base_id), Some(local_id), Some(remote_id)) if local_id == base_id => {
+                // Local didn't touch this name, remote's change wins.
+                merged.children.insert(name.clone(), *remote_id);
+            }
+            (Some(base_id), Some(local_id), Some(remote_id)) if remote_id == base_id => {
+                // Remote didn't touch this name, local's change wins.
+                merged.children.insert(name.clone(), *local_id);
+            }
+
+            // Neither matches base (or there was no base entry at all):
+            // both sides independently created a different entry under the
+            // same name. The remote entry is authoritative and keeps the
+            // name, the local one is renamed out of the way.
+            (_, Some(local_id), Some(remote_id)) => {
+                merged.children.insert(name.clone(), *remote_id);
+                let renamed = find_conflicting_name_for_child_entry(name, &merged.children, remote);
+                merged.children.insert(renamed.clone(), *local_id);
+                merged.renamed_conflicts.push((name.clone(), renamed));
+            }
+        }
+    }
+
+    merged
+}
+
+/// Find a name close to `name` that collides with neither `merged_so_far`
+/// nor `remote`, trying increasing numbered suffixes, e.g. `report.txt` ->
+/// `report (local conflict 1).txt`, `report (local conflict 2).txt`, ...
+fn find_conflicting_name_for_child_entry(
+    name: &EntryName,
+    merged_so_far: &HashMap<EntryName, VlobID>,
+    remote: &HashMap<EntryName, VlobID>,
+) -> EntryName {
+    for suffix in 1u32.. {
+        let candidate = conflict_name(name, suffix);
+        if !merged_so_far.contains_key(&candidate) && !remote.contains_key(&candidate) {
+            return candidate;
+        }
+    }
+    unreachable!("u32 suffixes are exhausted long before running out of memory")
+}
+
+/// Build a name for the local side of a concurrent-insert conflict, e.g.
+/// `report.txt` -> `report (local conflict 1).txt`.
+fn conflict_name(name: &EntryName, suffix: u32) -> EntryName {
+    let raw = name.as_ref();
+    let renamed = match raw.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => {
+            format!("{stem} (local conflict {suffix}).{ext}")
+        }
+        _ => format!("{raw} (local conflict {suffix})"),
+    };
+
+    EntryName::try_from(renamed.as_str()).unwrap_or_else(|_| name.clone())
+}
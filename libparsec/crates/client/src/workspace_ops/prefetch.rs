@@ -0,0 +1,230 @@
+// Parsec Cloud (https://parsec.cloud) Copyright (c) BUSL-1.1 2016-present Scille SAS
+
+//! Recursive subtree prefetch ("pull"), so a workspace subtree can be made
+//! available offline ahead of time instead of being fetched lazily the
+//! first time each entry is accessed.
+
+use globset::GlobMatcher;
+
+use super::entry_transactions::{get_child_manifest, resolve_path};
+use super::error_conversions::{impl_from_entry_id_from_path_error, impl_from_get_child_manifest_error};
+use super::fetch::{fetch_remote_block, FetchRemoteBlockError};
+use super::WorkspaceOps;
+use libparsec_types::prelude::*;
+
+#[derive(Debug, thiserror::Error)]
+pub enum PrefetchError {
+    #[error("Path doesn't exist")]
+    NotFound,
+    #[error("Cannot reach the server")]
+    Offline,
+    #[error("Not allowed to access this realm")]
+    NotAllowed,
+    #[error("Our clock ({client_timestamp}) and the server's one ({server_timestamp}) are too far apart")]
+    BadTimestamp {
+        server_timestamp: DateTime,
+        client_timestamp: DateTime,
+        ballpark_client_early_offset: f64,
+        ballpark_client_late_offset: f64,
+    },
+    #[error(transparent)]
+    Internal(#[from] anyhow::Error),
+}
+
+impl_from_entry_id_from_path_error!(PrefetchError);
+impl_from_get_child_manifest_error!(PrefetchError);
+
+impl From<FetchRemoteBlockError> for PrefetchError {
+    fn from(err: FetchRemoteBlockError) -> Self {
+        match err {
+            FetchRemoteBlockError::Offline => Self::Offline,
+            FetchRemoteBlockError::NotFound => Self::NotFound,
+            FetchRemoteBlockError::NotAllowed => Self::NotAllowed,
+            FetchRemoteBlockError::Internal(x) => Self::Internal(x),
+        }
+    }
+}
+
+/// How many files/folders/blocks `pull` actually touched, for progress
+/// reporting.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PrefetchStats {
+    pub files: usize,
+    pub folders: usize,
+    /// Blocks downloaded from the server because they weren't in local
+    /// storage yet, this is the part that makes the subtree offline-ready.
+    pub blocks_fetched: usize,
+    /// Blocks that were already present in local storage, counted
+    /// separately from `blocks_fetched` so a repeated `pull` is observably
+    /// idempotent (a second run should report zero fetches).
+    pub blocks_already_local: usize,
+    /// One entry per path the filter left out, with the reason, for a UI to
+    /// show which paths were skipped.
+    pub skipped: Vec<SkippedEntry>,
+}
+
+/// A path the prefetch walk visited but didn't fetch, and why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedEntry {
+    pub path: FsPath,
+    pub reason: SkipReason,
+    /// Set if the entry (or an ancestor) is confined by the workspace's
+    /// prevent-sync pattern, see `resolve_path`'s `confinement_point`. A
+    /// confined entry can still be selected by the filter rules, this is
+    /// purely informational for the UI.
+    pub confinement_point: Option<VlobID>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// The last matching rule (or the filter's default) was `Exclude`.
+    Excluded,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrefetchDecision {
+    Include,
+    Exclude,
+}
+
+/// One `include`/`exclude` rule matched against an entry's full
+/// workspace-relative path.
+#[derive(Debug, Clone)]
+pub struct PrefetchRule {
+    decision: PrefetchDecision,
+    matcher: GlobMatcher,
+}
+
+impl PrefetchRule {
+    pub fn new(decision: PrefetchDecision, matcher: GlobMatcher) -> Self {
+        Self { decision, matcher }
+    }
+}
+
+/// Selects which entries a `pull` actually fetches, evaluated against each
+/// entry's full workspace-relative path.
+///
+/// Rules are tried in order and the *last* one that matches wins, mirroring
+/// how ordered include/exclude groups work elsewhere (e.g. backup job
+/// filters): a broad early exclude can be carved back open by a later,
+/// more specific include, and vice versa. `default` applies when no rule
+/// matches at all. A folder that itself resolves to `Exclude` is still
+/// descended into, since it may contain children that resolve to
+/// `Include`, it is simply not counted as fetched on its own.
+#[derive(Debug, Clone)]
+pub struct PrefetchFilter {
+    rules: Vec<PrefetchRule>,
+    default: PrefetchDecision,
+}
+
+impl PrefetchFilter {
+    pub fn new(rules: Vec<PrefetchRule>, default: PrefetchDecision) -> Self {
+        Self { rules, default }
+    }
+
+    fn decide(&self, path: &FsPath) -> PrefetchDecision {
+        let path = path.to_string();
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.matcher.is_match(&path))
+            .map_or(self.default, |rule| rule.decision)
+    }
+}
+
+impl Default for PrefetchFilter {
+    /// No rules at all: every entry is included.
+    fn default() -> Self {
+        Self {
+            rules: Vec::new(),
+            default: PrefetchDecision::Include,
+        }
+    }
+}
+
+/// Recursively fetch `path` and everything below it matching `filter` into
+/// local storage, so it remains readable once the client goes offline. For
+/// files this means downloading every data block, not just the manifest.
+///
+/// Best-effort: prefetch stops and surfaces the first error encountered,
+/// whatever has already been fetched stays in local storage.
+pub(super) async fn pull(
+    ops: &WorkspaceOps,
+    path: &FsPath,
+    filter: &PrefetchFilter,
+) -> Result<PrefetchStats, PrefetchError> {
+    let resolution = resolve_path(ops, path).await?;
+    let mut stats = PrefetchStats::default();
+    pull_rec(
+        ops,
+        path,
+        resolution.entry_id,
+        resolution.confinement_point,
+        filter,
+        &mut stats,
+    )
+    .await?;
+    Ok(stats)
+}
+
+#[async_recursion::async_recursion]
+async fn pull_rec(
+    ops: &WorkspaceOps,
+    path: &FsPath,
+    entry_id: VlobID,
+    confinement_point: Option<VlobID>,
+    filter: &PrefetchFilter,
+    stats: &mut PrefetchStats,
+) -> Result<(), PrefetchError> {
+    let included = matches!(filter.decide(path), PrefetchDecision::Include);
+    if !included {
+        stats.skipped.push(SkippedEntry {
+            path: path.clone(),
+            reason: SkipReason::Excluded,
+            confinement_point,
+        });
+    }
+
+    match get_child_manifest(ops, entry_id).await? {
+        ArcLocalChildManifest::File(manifest) => {
+            if included {
+                stats.files += 1;
+                for access in manifest.blocks.iter().flatten() {
+                    if ops.data_storage.has_block(access.id).await? {
+                        stats.blocks_already_local += 1;
+                    } else {
+                        fetch_remote_block(ops, access).await?;
+                        stats.blocks_fetched += 1;
+                    }
+                }
+            }
+        }
+        ArcLocalChildManifest::Folder(manifest) => {
+            if included {
+                stats.folders += 1;
+            }
+            for (child_name, child_id) in manifest.children.iter() {
+                let child_path = path.join(child_name.to_owned());
+                // Top-most confinement point shadows child ones, same rule
+                // as `resolve_path`.
+                let child_confinement_point = match confinement_point {
+                    Some(_) => confinement_point,
+                    None => manifest
+                        .local_confinement_points
+                        .contains(child_id)
+                        .then_some(entry_id),
+                };
+                pull_rec(
+                    ops,
+                    &child_path,
+                    *child_id,
+                    child_confinement_point,
+                    filter,
+                    stats,
+                )
+                .await?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,49 @@
+// Parsec Cloud (https://parsec.cloud) Copyright (c) BUSL-1.1 (eventually AGPL-3.0) 2016-present Scille SAS
+
+//! Pluggable bearer-token authentication for `InvitedCmds`/`AuthenticatedCmds`.
+//!
+//! The built-in invitation token never expires and needs no `Authorization`
+//! header, but federated/SSO servers hand out short-lived tokens obtained
+//! from an external identity provider. `AuthProvider` lets the cmds factory
+//! stay agnostic of how a token was obtained while still being able to
+//! refresh it transparently before (or after a 401) sending a request.
+
+use async_trait::async_trait;
+
+use libparsec_types::DateTime;
+
+#[derive(Debug, thiserror::Error)]
+#[error("Failed to refresh the authentication token: {0}")]
+pub struct AuthRefreshError(pub String);
+
+/// A bearer token together with the instant it stops being valid.
+#[derive(Debug, Clone)]
+pub struct BearerToken {
+    pub value: String,
+    pub expires_at: DateTime,
+}
+
+/// Supplies the `Authorization` header value for every outgoing request and
+/// knows how to refresh it once expired.
+#[async_trait]
+pub trait AuthProvider: Send + Sync {
+    /// Current cached token, if any has been obtained yet.
+    fn current_token(&self) -> Option<BearerToken>;
+
+    /// Contact the identity provider to obtain a fresh token, and cache it.
+    async fn refresh(&self) -> Result<BearerToken, AuthRefreshError>;
+
+    /// Drop the cached token, forcing the next `authorization_header` call
+    /// to refresh (used after a server-side 401).
+    fn invalidate(&self);
+
+    /// Return a valid `Authorization` header value, refreshing first if the
+    /// cached token is missing or expired.
+    async fn authorization_header(&self, now: DateTime) -> Result<String, AuthRefreshError> {
+        let token = match self.current_token() {
+            Some(token) if token.expires_at > now => token,
+            _ => self.refresh().await?,
+        };
+        Ok(format!("Bearer {}", token.value))
+    }
+}
@@ -1,13 +1,16 @@
 // Parsec Cloud (https://parsec.cloud) Copyright (c) BUSL-1.1 (eventually AGPL-3.0) 2016-present Scille SAS
 
+use std::sync::Arc;
+
 use libparsec_protocol::Request;
-use libparsec_types::BackendInvitationAddr;
+use libparsec_types::{BackendInvitationAddr, DateTime};
 use reqwest::{
-    header::{HeaderMap, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE},
+    header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE},
     Client, RequestBuilder, Url,
 };
 
 use crate::{
+    auth_provider::AuthProvider,
     error::{CommandError, CommandResult},
     API_VERSION_HEADER_NAME, PARSEC_CONTENT_TYPE,
 };
@@ -18,6 +21,9 @@ pub struct InvitedCmds {
     client: Client,
     addr: BackendInvitationAddr,
     url: Url,
+    /// Optional federated/SSO token provider, `None` means the built-in
+    /// invitation token is used and no `Authorization` header is sent.
+    auth_provider: Option<Arc<dyn AuthProvider>>,
 }
 
 impl InvitedCmds {
@@ -29,7 +35,19 @@ impl InvitedCmds {
             addr.token()
         )));
 
-        Ok(Self { client, addr, url })
+        Ok(Self {
+            client,
+            addr,
+            url,
+            auth_provider: None,
+        })
+    }
+
+    /// Have every subsequent `send` inject an `Authorization` header obtained
+    /// (and refreshed, on expiry or 401) through `auth_provider`.
+    pub fn with_auth_provider(mut self, auth_provider: Arc<dyn AuthProvider>) -> Self {
+        self.auth_provider = Some(auth_provider);
+        self
     }
 
     pub fn addr(&self) -> &BackendInvitationAddr {
@@ -38,8 +56,12 @@ impl InvitedCmds {
 }
 
 /// Prepare a new request, the body will be added to the Request using [RequestBuilder::body]
-fn prepare_request(request_builder: RequestBuilder, body: Vec<u8>) -> RequestBuilder {
-    let mut content_headers = HeaderMap::with_capacity(2);
+fn prepare_request(
+    request_builder: RequestBuilder,
+    body: Vec<u8>,
+    authorization: Option<&str>,
+) -> RequestBuilder {
+    let mut content_headers = HeaderMap::with_capacity(4);
     content_headers.insert(
         API_VERSION_HEADER_NAME,
         HeaderValue::from_str(&libparsec_protocol::API_VERSION.to_string())
@@ -50,11 +72,29 @@ fn prepare_request(request_builder: RequestBuilder, body: Vec<u8>) -> RequestBui
         CONTENT_LENGTH,
         HeaderValue::from_str(&body.len().to_string()).expect("numeric value are valid char"),
     );
+    if let Some(authorization) = authorization {
+        content_headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(authorization).expect("authorization header must contain valid char"),
+        );
+    }
 
     request_builder.headers(content_headers).body(body)
 }
 
 impl InvitedCmds {
+    async fn authorization_header(&self) -> CommandResult<Option<String>> {
+        match &self.auth_provider {
+            None => Ok(None),
+            Some(auth_provider) => Ok(Some(
+                auth_provider
+                    .authorization_header(DateTime::now())
+                    .await
+                    .map_err(|err| CommandError::BadAuthentication(err.to_string()))?,
+            )),
+        }
+    }
+
     pub async fn send<T>(
         &self,
         request: T,
@@ -62,17 +102,40 @@ impl InvitedCmds {
     where
         T: Request,
     {
-        let request_builder = self.client.post(self.url.clone());
-
         let data = request.dump()?;
 
-        let req = prepare_request(request_builder, data).send();
+        let authorization = self.authorization_header().await?;
+        let req = prepare_request(
+            self.client.post(self.url.clone()),
+            data.clone(),
+            authorization.as_deref(),
+        )
+        .send();
         let resp = req.await?;
+
+        // A federated token can expire between the freshness check above and
+        // the server processing the request: on a 401 we invalidate the
+        // cached token, refresh once, and retry the request exactly once.
+        let resp = if resp.status().as_u16() == 401 && self.auth_provider.is_some() {
+            if let Some(auth_provider) = &self.auth_provider {
+                auth_provider.invalidate();
+            }
+            let authorization = self.authorization_header().await?;
+            prepare_request(self.client.post(self.url.clone()), data, authorization.as_deref())
+                .send()
+                .await?
+        } else {
+            resp
+        };
+
         match resp.status().as_u16() {
             200 => {
                 let response_body = resp.bytes().await?;
                 Ok(T::load_response(&response_body)?)
             }
+            401 => Err(CommandError::BadAuthentication(
+                "authentication token rejected by the server".to_owned(),
+            )),
             404 => Err(CommandError::InvitationNotFound),
             410 => Err(CommandError::InvitationAlreadyDeleted),
             415 => Err(CommandError::BadContent),
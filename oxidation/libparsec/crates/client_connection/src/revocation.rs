@@ -0,0 +1,273 @@
+// Parsec Cloud (https://parsec.cloud) Copyright (c) BUSL-1.1 (eventually AGPL-3.0) 2016-present Scille SAS
+
+//! Revocation checking for PKI enrollment certificates, layered on top of the
+//! chain/signature verification in `libparsec_types::x509`.
+//!
+//! An accepter uses this to make sure a submitter certificate that still
+//! chains to a trusted root hasn't since been revoked (compromised device,
+//! departed employee, ...). OCSP is preferred when the certificate advertises
+//! a responder, CRL distribution points are not queried yet.
+
+use ocsp::common::asn1::{CertId, GeneralizedTime};
+use ocsp::common::ocsp_ext::OcspExt;
+use ocsp::request::{OcspRequest, Request as OcspSingleRequest, TbsRequest};
+use ocsp::response::{CertStatus, OcspResponse, OcspResponseStatus, ResponderId};
+use rand::RngCore;
+use reqwest::Client;
+use ring::signature::UnparsedPublicKey;
+use sha1::{Digest, Sha1};
+use x509_parser::certificate::X509Certificate;
+use x509_parser::extensions::ParsedExtension;
+use x509_parser::prelude::FromDer;
+
+use libparsec_types::x509::AllowedSignatureAlgorithm;
+use libparsec_types::DateTime;
+
+use crate::error::CommandError;
+
+/// Size of the request nonce (RFC 8954 recommends at least 1 byte, at most
+/// 32), mirroring what most CA responders echo back.
+const NONCE_SIZE: usize = 16;
+
+/// How far in the future `thisUpdate` is still tolerated, to absorb clock
+/// skew between us and the responder rather than rejecting a response it
+/// just issued.
+const THIS_UPDATE_SKEW_TOLERANCE_SECS: f64 = 300.0;
+
+/// Whether the caller should treat an `Unknown` revocation status (responder
+/// unreachable, malformed response, no AIA extension at all, ...) as a hard
+/// rejection or as a tolerated best-effort check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RevocationCheckPolicy {
+    /// `Unknown` aborts the `pki_enrollment_accept` flow.
+    HardFail,
+    /// `Unknown` is treated the same as `Good`.
+    SoftFail,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RevocationStatus {
+    Good,
+    Revoked {
+        reason: Option<String>,
+        revoked_on: DateTime,
+    },
+    Unknown,
+}
+
+/// Resolve `RevocationStatus` into a go/no-go decision according to `policy`.
+impl RevocationStatus {
+    pub fn is_accepted(&self, policy: RevocationCheckPolicy) -> bool {
+        match (self, policy) {
+            (Self::Good, _) => true,
+            (Self::Revoked { .. }, _) => false,
+            (Self::Unknown, RevocationCheckPolicy::HardFail) => false,
+            (Self::Unknown, RevocationCheckPolicy::SoftFail) => true,
+        }
+    }
+}
+
+fn ocsp_responder_url(cert: &X509Certificate) -> Option<String> {
+    cert.extensions().iter().find_map(|ext| match ext.parsed_extension() {
+        ParsedExtension::AuthorityInfoAccess(aia) => aia
+            .accessdescs
+            .iter()
+            .find(|desc| desc.access_method.to_string() == "1.3.6.1.5.5.7.48.1")
+            .and_then(|desc| desc.access_location.uri().map(str::to_owned)),
+        _ => None,
+    })
+}
+
+/// Fetch and validate the OCSP status of `submitter_der_x509_certificate`
+/// against its issuer, reusing the `reqwest::Client` already held by the cmds
+/// factory. Returns `Unknown` (never an error) whenever the responder cannot
+/// be reached or the response fails to parse/verify, it is up to the caller
+/// to apply `RevocationCheckPolicy`.
+pub async fn check_revocation_status(
+    client: &Client,
+    submitter_der: &[u8],
+    issuer_der: &[u8],
+    now: DateTime,
+) -> Result<RevocationStatus, CommandError> {
+    let (_, cert) = match X509Certificate::from_der(submitter_der) {
+        Ok(parsed) => parsed,
+        Err(_) => return Ok(RevocationStatus::Unknown),
+    };
+    let (_, issuer_cert) = match X509Certificate::from_der(issuer_der) {
+        Ok(parsed) => parsed,
+        Err(_) => return Ok(RevocationStatus::Unknown),
+    };
+
+    let Some(responder_url) = ocsp_responder_url(&cert) else {
+        return Ok(RevocationStatus::Unknown);
+    };
+
+    let cert_id = build_cert_id(&cert, &issuer_cert);
+    let mut nonce = vec![0u8; NONCE_SIZE];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let Some(ocsp_request) = build_ocsp_request(&cert_id, &nonce) else {
+        return Ok(RevocationStatus::Unknown);
+    };
+
+    let response = client
+        .post(responder_url)
+        .header("Content-Type", "application/ocsp-request")
+        .body(ocsp_request)
+        .send()
+        .await;
+
+    let Ok(response) = response else {
+        return Ok(RevocationStatus::Unknown);
+    };
+
+    if !response.status().is_success() {
+        return Ok(RevocationStatus::Unknown);
+    }
+
+    let Ok(body) = response.bytes().await else {
+        return Ok(RevocationStatus::Unknown);
+    };
+
+    Ok(parse_ocsp_response(&body, &issuer_cert, &cert_id, &nonce, now))
+}
+
+/// Build the CertID (issuer name hash, issuer key hash, serial number) that
+/// identifies `cert` to the OCSP responder, hashed with SHA-1 as mandated by
+/// RFC 6960 for `CertID.hashAlgorithm`.
+fn build_cert_id(cert: &X509Certificate, issuer_cert: &X509Certificate) -> CertId {
+    let issuer_name_hash = Sha1::digest(issuer_cert.subject().as_raw()).to_vec();
+    // `SubjectPublicKeyInfo.subjectPublicKey` itself (not the whole SPKI,
+    // and not including the unused-bits leading byte of the BIT STRING).
+    let issuer_key_hash = Sha1::digest(&issuer_cert.public_key().subject_public_key.data).to_vec();
+    let serial_number = cert.raw_serial().to_vec();
+
+    CertId::new("1.3.14.3.2.26", &issuer_name_hash, &issuer_key_hash, &serial_number)
+}
+
+/// Wrap `cert_id` in a DER-encoded `OCSPRequest` with a single `Request`,
+/// carrying `nonce` as the RFC 8954 nonce extension so the matching
+/// response can be tied back to this exact request instead of a stale one
+/// the responder (or a man-in-the-middle) replays.
+fn build_ocsp_request(cert_id: &CertId, nonce: &[u8]) -> Option<Vec<u8>> {
+    let request = OcspSingleRequest {
+        req_cert: cert_id.clone(),
+        single_request_extensions: None,
+    };
+    let tbs_request = TbsRequest {
+        version: 0,
+        requestor_name: None,
+        request_list: vec![request],
+        request_extensions: Some(vec![OcspExt::Nonce {
+            nonce: nonce.to_vec(),
+        }]),
+    };
+    OcspRequest {
+        tbs_request,
+        optional_signature: None,
+    }
+    .to_der()
+    .ok()
+}
+
+/// Verify the OCSP response signature against the issuer certificate, that
+/// it actually answers this request (echoed nonce) and is still fresh
+/// (`thisUpdate`/`nextUpdate`), and map the embedded `CertStatus` for
+/// `cert_id` to our `RevocationStatus`.
+fn parse_ocsp_response(
+    response_der: &[u8],
+    issuer_cert: &X509Certificate,
+    cert_id: &CertId,
+    request_nonce: &[u8],
+    now: DateTime,
+) -> RevocationStatus {
+    let Ok(response) = OcspResponse::parse(response_der) else {
+        return RevocationStatus::Unknown;
+    };
+
+    if response.resp_status != OcspResponseStatus::Successful {
+        return RevocationStatus::Unknown;
+    }
+
+    let Some(basic_response) = response.basic_response() else {
+        return RevocationStatus::Unknown;
+    };
+
+    // The responder may be the issuer itself (most common for a CA's own
+    // OCSP endpoint) or a delegated responder, we only support the former:
+    // verify the response signature directly against the issuer's key.
+    let Some(algo) = AllowedSignatureAlgorithm::from_oid(&basic_response.signature_algorithm.to_string())
+    else {
+        return RevocationStatus::Unknown;
+    };
+    let verifies = UnparsedPublicKey::new(
+        algo.verification_algorithm(),
+        &issuer_cert.public_key().subject_public_key.data,
+    )
+        .verify(&basic_response.tbs_response_data_der, &basic_response.signature)
+        .is_ok();
+    if !verifies {
+        return RevocationStatus::Unknown;
+    }
+
+    // A response without our nonce (or echoing the wrong one) cannot be
+    // trusted to answer this specific request, it could be a stale response
+    // replayed from an earlier, now-outdated query.
+    let echoes_our_nonce = basic_response
+        .tbs_response_data
+        .response_extensions
+        .iter()
+        .flatten()
+        .any(|ext| matches!(ext, OcspExt::Nonce { nonce } if nonce == request_nonce));
+    if !echoes_our_nonce {
+        return RevocationStatus::Unknown;
+    }
+
+    let matching_response = basic_response
+        .tbs_response_data
+        .responses
+        .iter()
+        .find(|single| single.cert_id == *cert_id);
+
+    match matching_response {
+        Some(single) => {
+            if !is_fresh(single, now) {
+                return RevocationStatus::Unknown;
+            }
+            match &single.cert_status {
+                CertStatus::Good => RevocationStatus::Good,
+                CertStatus::Revoked {
+                    revocation_time,
+                    revocation_reason,
+                } => RevocationStatus::Revoked {
+                    reason: revocation_reason.clone(),
+                    revoked_on: generalized_time_to_datetime(revocation_time),
+                },
+                CertStatus::Unknown => RevocationStatus::Unknown,
+            }
+        }
+        None => RevocationStatus::Unknown,
+    }
+}
+
+/// Whether `single`'s validity window (`thisUpdate`/`nextUpdate`) still
+/// covers `now`, so a captured `Good` response from before a since-applied
+/// revocation can't be replayed indefinitely.
+fn is_fresh(single: &ocsp::response::OneResp, now: DateTime) -> bool {
+    let now_ts = now.get_f64_with_us_precision();
+
+    let this_update = single.this_update.timestamp() as f64;
+    if this_update - THIS_UPDATE_SKEW_TOLERANCE_SECS > now_ts {
+        return false;
+    }
+
+    match &single.next_update {
+        Some(next_update) => (next_update.timestamp() as f64) >= now_ts,
+        // No `nextUpdate` means the responder makes no promise about when a
+        // fresher status will be available, nothing to bound it against.
+        None => true,
+    }
+}
+
+fn generalized_time_to_datetime(time: &GeneralizedTime) -> DateTime {
+    DateTime::from_f64_with_us_precision(time.timestamp() as f64)
+}
@@ -11,6 +11,68 @@ use libparsec_crypto::{PrivateKey, PublicKey, SecretKey, SigningKey, VerifyKey};
 
 use crate::{DataError, DateTime, DeviceID, EntryName, IndexInt, RealmID};
 
+/// Algorithm used to sign the serialized `MessageContent`, prefixed to the
+/// compressed blob so a future algorithm can be introduced without breaking
+/// older clients reading newer messages (and vice versa).
+///
+/// Unlike the legacy format, the prefix carrying this (see [`MAGIC`]) sits
+/// *outside* the signature, so the reader can pick which verification
+/// primitive to use before it even looks at the signed bytes, instead of
+/// committing to `VerifyKey`/Ed25519 up front.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SignatureAlgorithm {
+    /// `SigningKey`/`VerifyKey` (Ed25519), the only scheme supported so far.
+    Ed25519 = 0,
+}
+
+impl SignatureAlgorithm {
+    fn from_u8(value: u8) -> Result<Self, DataError> {
+        match value {
+            0 => Ok(Self::Ed25519),
+            _ => Err(DataError::Signature),
+        }
+    }
+}
+
+/// Compression algorithm applied to the serialized `MessageContent` before
+/// signing, prefixed alongside the signature algorithm for the same reason.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum CompressionAlgorithm {
+    /// No compression.
+    None = 0,
+    /// `flate2`'s zlib, the only scheme supported so far.
+    Zlib = 1,
+}
+
+impl CompressionAlgorithm {
+    fn from_u8(value: u8) -> Result<Self, DataError> {
+        match value {
+            0 => Ok(Self::None),
+            1 => Ok(Self::Zlib),
+            _ => Err(DataError::Compression),
+        }
+    }
+}
+
+/// Algorithms used by [`MessageContent::dump_sign_and_encrypt_for`] when the
+/// caller has no specific reason to pick otherwise, kept as a single tuple
+/// so both prefix bytes are updated together.
+pub const CURRENT_ALGORITHMS: (SignatureAlgorithm, CompressionAlgorithm) =
+    (SignatureAlgorithm::Ed25519, CompressionAlgorithm::Zlib);
+
+/// Marks a signed blob as using the negotiable-algorithm prefix format.
+///
+/// Messages signed before this format was introduced carry no marker: they
+/// are the Ed25519 signed blob (signature followed by the zlib stream), so
+/// their first byte is the signature's and can happen to be `0xFF`. A blob
+/// starting with `MAGIC` is therefore only read in the new format when it
+/// does not verify as a whole legacy blob: older clients' messages keep
+/// loading as zlib, new ones carry `MAGIC` followed by the 2-byte algorithm
+/// prefix.
+const MAGIC: u8 = 0xFF;
+
 #[serde_as]
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(tag = "type")]
@@ -71,16 +133,48 @@ impl MessageContent {
         expected_author: &DeviceID,
         expected_timestamp: DateTime,
     ) -> Result<MessageContent, DataError> {
-        let signed = recipient_privkey
+        let plain = recipient_privkey
             .decrypt_from_self(ciphered)
             .map_err(|_| DataError::Decryption)?;
-        let compressed = author_verify_key
-            .verify(&signed)
-            .map_err(|_| DataError::Signature)?;
+
         let mut serialized = vec![];
-        ZlibDecoder::new(compressed)
-            .read_to_end(&mut serialized)
-            .map_err(|_| DataError::Compression)?;
+        match plain.first() {
+            // New format: `MAGIC`, then the 2-byte algorithm prefix, all
+            // ahead of the signature so the algorithm to verify with can be
+            // picked before touching the signed bytes. A legacy blob whose
+            // signature happens to start with `MAGIC` still verifies as a
+            // whole and falls through to the legacy arm.
+            Some(&MAGIC) if author_verify_key.verify(&plain).is_err() => {
+                if plain.len() < 3 {
+                    return Err(DataError::Signature);
+                }
+                let (signature_algorithm, compression_algorithm) = (plain[1], plain[2]);
+                let signed = &plain[3..];
+                let compressed = match SignatureAlgorithm::from_u8(signature_algorithm)? {
+                    SignatureAlgorithm::Ed25519 => author_verify_key
+                        .verify(signed)
+                        .map_err(|_| DataError::Signature)?,
+                };
+                match CompressionAlgorithm::from_u8(compression_algorithm)? {
+                    CompressionAlgorithm::Zlib => {
+                        ZlibDecoder::new(&compressed[..])
+                            .read_to_end(&mut serialized)
+                            .map_err(|_| DataError::Compression)?;
+                    }
+                    CompressionAlgorithm::None => serialized.extend_from_slice(&compressed),
+                }
+            }
+            // Legacy format, predating algorithm negotiation: a bare zlib
+            // stream signed with Ed25519, with no prefix at all.
+            _ => {
+                let signed = author_verify_key
+                    .verify(&plain)
+                    .map_err(|_| DataError::Signature)?;
+                ZlibDecoder::new(&signed[..])
+                    .read_to_end(&mut serialized)
+                    .map_err(|_| DataError::Compression)?;
+            }
+        }
         let data: MessageContent =
             rmp_serde::from_slice(&serialized).map_err(|_| DataError::Serialization)?;
         let (author, &timestamp) = match &data {
@@ -112,16 +206,37 @@ impl MessageContent {
         }
     }
 
+    /// Sign, compress and encrypt `self` using `algorithms`, pass
+    /// [`CURRENT_ALGORITHMS`] unless the caller has a specific reason (e.g.
+    /// interop testing) to pick another supported combination.
     pub fn dump_sign_and_encrypt_for(
         &self,
         author_signkey: &SigningKey,
         recipient_pubkey: &PublicKey,
+        algorithms: (SignatureAlgorithm, CompressionAlgorithm),
     ) -> Vec<u8> {
         let serialized = rmp_serde::to_vec_named(&self).unwrap_or_else(|_| unreachable!());
-        let mut e = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
-        e.write_all(&serialized).unwrap_or_else(|_| unreachable!());
-        let compressed = e.finish().unwrap_or_else(|_| unreachable!());
-        let signed = author_signkey.sign(&compressed);
-        recipient_pubkey.encrypt_for_self(&signed)
+        let (signature_algorithm, compression_algorithm) = algorithms;
+
+        let compressed = match compression_algorithm {
+            CompressionAlgorithm::Zlib => {
+                let mut e = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                e.write_all(&serialized).unwrap_or_else(|_| unreachable!());
+                e.finish().unwrap_or_else(|_| unreachable!())
+            }
+            CompressionAlgorithm::None => serialized,
+        };
+
+        let signed = match signature_algorithm {
+            SignatureAlgorithm::Ed25519 => author_signkey.sign(&compressed),
+        };
+
+        let mut prefixed = Vec::with_capacity(3 + signed.len());
+        prefixed.push(MAGIC);
+        prefixed.push(signature_algorithm as u8);
+        prefixed.push(compression_algorithm as u8);
+        prefixed.extend_from_slice(&signed);
+
+        recipient_pubkey.encrypt_for_self(&prefixed)
     }
 }
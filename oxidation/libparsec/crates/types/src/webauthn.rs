@@ -0,0 +1,369 @@
+// Parsec Cloud (https://parsec.cloud) Copyright (c) BUSL-1.1 (eventually AGPL-3.0) 2016-present Scille SAS
+
+//! FIDO2/WebAuthn device enrollment, a token-based alternative to the
+//! `pki_enrollment_*` family (see `x509`) for organizations without a
+//! corporate PKI.
+//!
+//! Registration ingests an attestation object carrying the credential ID and
+//! a COSE-encoded public key. Later, the accept step challenges the device
+//! and verifies the returned assertion signature, rejecting any assertion
+//! whose signature counter did not strictly increase (clone detection).
+
+use async_trait::async_trait;
+use ciborium::value::Value as CborValue;
+use ring::signature::{UnparsedPublicKey, ECDSA_P256_SHA256_ASN1};
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+use sha2::{Digest, Sha256};
+
+use libparsec_crypto::{CryptoError, VerifyKey};
+
+/// COSE key algorithm identifiers we accept for WebAuthn credentials.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CoseAlgorithm {
+    #[serde(rename = "eddsa")]
+    EdDSA,
+    #[serde(rename = "es256")]
+    Es256,
+}
+
+/// A WebAuthn credential registered during `webauthn_enrollment_submit`,
+/// stored server-side so subsequent `webauthn_enrollment_accept` challenges
+/// can be verified against it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebAuthnCredential {
+    pub credential_id: ByteBuf,
+    pub algorithm: CoseAlgorithm,
+    /// Raw COSE_Key encoded public key, as returned by the authenticator.
+    pub public_key: ByteBuf,
+    /// Signature counter from the registration ceremony, every subsequent
+    /// assertion must report a strictly greater value.
+    pub sign_count: u32,
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum WebAuthnAttestationError {
+    #[error("Attestation object is not valid CBOR")]
+    InvalidCbor,
+    #[error("Attestation object is missing its authenticator data")]
+    MissingAuthenticatorData,
+    #[error("Authenticator data is truncated")]
+    TruncatedAuthenticatorData,
+    #[error("Authenticator data has no attested credential data (flag not set)")]
+    NoAttestedCredentialData,
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum WebAuthnAssertionError {
+    #[error("COSE public key could not be converted to a verify key")]
+    InvalidPublicKey,
+    #[error("Assertion signature does not match the stored credential public key")]
+    InvalidSignature,
+    #[error("Signature counter did not strictly increase, possible cloned authenticator")]
+    CounterDidNotIncrease,
+}
+
+/// A `webauthn_enrollment_accept` assertion returned by the device in
+/// response to a server-issued challenge.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebAuthnAssertion {
+    pub authenticator_data: ByteBuf,
+    pub client_data_json: ByteBuf,
+    pub signature: ByteBuf,
+    pub sign_count: u32,
+}
+
+/// A credential's public key decoded out of its COSE_Key bytes, in whichever
+/// shape the matching signature primitive needs it in.
+enum CredentialVerifyKey {
+    Ed25519(VerifyKey),
+    /// Uncompressed SEC1 point (`0x04 || X || Y`), as `ring` expects it.
+    Es256(Vec<u8>),
+}
+
+fn cbor_map(bytes: &[u8]) -> Result<Vec<(CborValue, CborValue)>, CryptoError> {
+    match ciborium::de::from_reader(bytes).map_err(|_| CryptoError::DataSize)? {
+        CborValue::Map(map) => Ok(map),
+        _ => Err(CryptoError::DataSize),
+    }
+}
+
+/// Look up a COSE_Key map entry by its integer key (COSE labels are negative
+/// for key-type-specific parameters, e.g. `-2`/`-3` for the x/y coordinates).
+fn cose_map_get<'a>(map: &'a [(CborValue, CborValue)], label: i128) -> Option<&'a [u8]> {
+    map.iter().find_map(|(key, value)| match key {
+        CborValue::Integer(key) if i128::from(*key) == label => value.as_bytes().map(Vec::as_slice),
+        _ => None,
+    })
+}
+
+impl WebAuthnCredential {
+    /// Parse the CBOR attestation object an authenticator returns during
+    /// `webauthn_enrollment_submit`, extracting the credential id and
+    /// COSE-encoded public key out of its `authData` field.
+    ///
+    /// The attestation statement (`attStmt`) itself, which chains the
+    /// authenticator's own attestation certificate back to a manufacturer
+    /// root, is intentionally not verified: like most WebAuthn relying
+    /// parties we trust the channel the attestation object travelled over
+    /// rather than vet the authenticator's hardware provenance.
+    pub fn from_attestation_object(
+        attestation_object: &[u8],
+        algorithm: CoseAlgorithm,
+    ) -> Result<Self, WebAuthnAttestationError> {
+        let map: CborValue = ciborium::de::from_reader(attestation_object)
+            .map_err(|_| WebAuthnAttestationError::InvalidCbor)?;
+        let map = match map {
+            CborValue::Map(map) => map,
+            _ => return Err(WebAuthnAttestationError::InvalidCbor),
+        };
+        let auth_data = map
+            .iter()
+            .find_map(|(key, value)| match key {
+                CborValue::Text(key) if key == "authData" => value.as_bytes(),
+                _ => None,
+            })
+            .ok_or(WebAuthnAttestationError::MissingAuthenticatorData)?;
+
+        // authData layout: rpIdHash (32) || flags (1) || signCount (4, BE)
+        // || attestedCredentialData (only present if the flag below is set).
+        if auth_data.len() < 37 {
+            return Err(WebAuthnAttestationError::TruncatedAuthenticatorData);
+        }
+        const ATTESTED_CREDENTIAL_DATA_PRESENT: u8 = 0x40;
+        let flags = auth_data[32];
+        if flags & ATTESTED_CREDENTIAL_DATA_PRESENT == 0 {
+            return Err(WebAuthnAttestationError::NoAttestedCredentialData);
+        }
+        let sign_count = u32::from_be_bytes(
+            auth_data[33..37]
+                .try_into()
+                .expect("slice is exactly 4 bytes"),
+        );
+
+        // attestedCredentialData: aaguid (16) || credIdLen (2, BE) || credId
+        // || COSE_Key-encoded public key.
+        let attested = &auth_data[37..];
+        if attested.len() < 18 {
+            return Err(WebAuthnAttestationError::TruncatedAuthenticatorData);
+        }
+        let credential_id_len = u16::from_be_bytes([attested[16], attested[17]]) as usize;
+        let credential_id_start = 18;
+        let credential_id_end = credential_id_start + credential_id_len;
+        if attested.len() < credential_id_end {
+            return Err(WebAuthnAttestationError::TruncatedAuthenticatorData);
+        }
+
+        Ok(Self {
+            credential_id: ByteBuf::from(attested[credential_id_start..credential_id_end].to_vec()),
+            algorithm,
+            public_key: ByteBuf::from(attested[credential_id_end..].to_vec()),
+            sign_count,
+        })
+    }
+
+    /// Decode the stored COSE_Key into a usable verification key, dispatched
+    /// on `algorithm`: an OKP key for `EdDSA` (mapping directly onto our
+    /// Ed25519-based `VerifyKey`), or an EC2 key for `Es256` (reassembled
+    /// into the uncompressed SEC1 point `ring`'s ECDSA verifier expects).
+    fn verify_key(&self) -> Result<CredentialVerifyKey, CryptoError> {
+        let map = cbor_map(&self.public_key)?;
+        match self.algorithm {
+            CoseAlgorithm::EdDSA => {
+                // COSE OKP key, label -2 is `x`, the raw 32-byte public key.
+                let x = cose_map_get(&map, -2).ok_or(CryptoError::DataSize)?;
+                VerifyKey::try_from(x).map(CredentialVerifyKey::Ed25519)
+            }
+            CoseAlgorithm::Es256 => {
+                // COSE EC2 key, labels -2/-3 are the `x`/`y` coordinates.
+                let x = cose_map_get(&map, -2).ok_or(CryptoError::DataSize)?;
+                let y = cose_map_get(&map, -3).ok_or(CryptoError::DataSize)?;
+                if x.len() != 32 || y.len() != 32 {
+                    return Err(CryptoError::DataSize);
+                }
+                let mut point = Vec::with_capacity(1 + x.len() + y.len());
+                point.push(0x04);
+                point.extend_from_slice(x);
+                point.extend_from_slice(y);
+                Ok(CredentialVerifyKey::Es256(point))
+            }
+        }
+    }
+
+    /// Verify `assertion` was produced by this credential: the signature
+    /// covers `authenticator_data || SHA-256(client_data_json)`, and the
+    /// counter embedded in `authenticator_data` must have strictly increased
+    /// since registration/last use.
+    ///
+    /// The counter is read out of the signed `authenticator_data` bytes
+    /// rather than `assertion.sign_count`: the latter is a caller-supplied
+    /// struct field that isn't covered by the signature, so trusting it
+    /// would let a replayed genuine assertion pass clone detection simply by
+    /// lying about that field.
+    pub fn verify_assertion(
+        &mut self,
+        assertion: &WebAuthnAssertion,
+    ) -> Result<(), WebAuthnAssertionError> {
+        let sign_count = Self::signed_counter(&assertion.authenticator_data)?;
+        if sign_count <= self.sign_count {
+            return Err(WebAuthnAssertionError::CounterDidNotIncrease);
+        }
+
+        let verify_key = self
+            .verify_key()
+            .map_err(|_| WebAuthnAssertionError::InvalidPublicKey)?;
+
+        let mut signed_data = assertion.authenticator_data.to_vec();
+        signed_data.extend_from_slice(&Sha256::digest(&assertion.client_data_json[..]));
+
+        match verify_key {
+            CredentialVerifyKey::Ed25519(key) => key
+                .verify_with_signature(&assertion.signature, &signed_data)
+                .map_err(|_| WebAuthnAssertionError::InvalidSignature)?,
+            CredentialVerifyKey::Es256(point) => {
+                UnparsedPublicKey::new(&ECDSA_P256_SHA256_ASN1, &point)
+                    .verify(&signed_data, &assertion.signature)
+                    .map_err(|_| WebAuthnAssertionError::InvalidSignature)?;
+            }
+        }
+
+        self.sign_count = sign_count;
+        Ok(())
+    }
+
+    /// Extract the signature counter embedded in `authenticator_data` at
+    /// bytes `[33..37]` (big-endian u32), the same layout parsed in
+    /// `from_attestation_object`.
+    fn signed_counter(authenticator_data: &[u8]) -> Result<u32, WebAuthnAssertionError> {
+        let counter_bytes = authenticator_data
+            .get(33..37)
+            .ok_or(WebAuthnAssertionError::InvalidSignature)?;
+        Ok(u32::from_be_bytes(
+            counter_bytes
+                .try_into()
+                .expect("slice is exactly 4 bytes"),
+        ))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum WebAuthnCommandError {
+    #[error("Invalid msgpack data: {0}")]
+    Serialization(rmp_serde::encode::Error),
+    #[error("Invalid msgpack data: {0}")]
+    Deserialization(rmp_serde::decode::Error),
+}
+
+/// Request body of `webauthn_enrollment_submit`: carries the attestation
+/// object a freshly-registered authenticator produced, for the server to
+/// validate (via [`WebAuthnCredential::from_attestation_object`]) and store.
+///
+/// Follows the same serde + msgpack `dump`/`load` roundtrip convention as
+/// the `pki_enrollment_submit` command.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebAuthnEnrollmentSubmitReq {
+    pub attestation_object: ByteBuf,
+    pub algorithm: CoseAlgorithm,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status")]
+pub enum WebAuthnEnrollmentSubmitRep {
+    #[serde(rename = "ok")]
+    Ok,
+    #[serde(rename = "invalid_attestation")]
+    InvalidAttestation { reason: String },
+}
+
+impl WebAuthnEnrollmentSubmitReq {
+    pub fn dump(&self) -> Result<Vec<u8>, WebAuthnCommandError> {
+        rmp_serde::to_vec_named(self).map_err(WebAuthnCommandError::Serialization)
+    }
+
+    pub fn load(raw: &[u8]) -> Result<Self, WebAuthnCommandError> {
+        rmp_serde::from_slice(raw).map_err(WebAuthnCommandError::Deserialization)
+    }
+}
+
+impl WebAuthnEnrollmentSubmitRep {
+    pub fn dump(&self) -> Result<Vec<u8>, WebAuthnCommandError> {
+        rmp_serde::to_vec_named(self).map_err(WebAuthnCommandError::Serialization)
+    }
+
+    pub fn load(raw: &[u8]) -> Result<Self, WebAuthnCommandError> {
+        rmp_serde::from_slice(raw).map_err(WebAuthnCommandError::Deserialization)
+    }
+}
+
+/// Request body of `webauthn_enrollment_accept`: the device's response to a
+/// server-issued challenge, to be checked with
+/// [`WebAuthnCredential::verify_assertion`] against the credential stored
+/// during `webauthn_enrollment_submit`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebAuthnEnrollmentAcceptReq {
+    pub assertion: WebAuthnAssertion,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status")]
+pub enum WebAuthnEnrollmentAcceptRep {
+    #[serde(rename = "ok")]
+    Ok,
+    #[serde(rename = "invalid_signature")]
+    InvalidSignature,
+    #[serde(rename = "counter_did_not_increase")]
+    CounterDidNotIncrease,
+}
+
+impl WebAuthnEnrollmentAcceptReq {
+    pub fn dump(&self) -> Result<Vec<u8>, WebAuthnCommandError> {
+        rmp_serde::to_vec_named(self).map_err(WebAuthnCommandError::Serialization)
+    }
+
+    pub fn load(raw: &[u8]) -> Result<Self, WebAuthnCommandError> {
+        rmp_serde::from_slice(raw).map_err(WebAuthnCommandError::Deserialization)
+    }
+}
+
+impl WebAuthnEnrollmentAcceptRep {
+    pub fn dump(&self) -> Result<Vec<u8>, WebAuthnCommandError> {
+        rmp_serde::to_vec_named(self).map_err(WebAuthnCommandError::Serialization)
+    }
+
+    pub fn load(raw: &[u8]) -> Result<Self, WebAuthnCommandError> {
+        rmp_serde::from_slice(raw).map_err(WebAuthnCommandError::Deserialization)
+    }
+}
+
+impl From<&WebAuthnAssertionError> for WebAuthnEnrollmentAcceptRep {
+    fn from(err: &WebAuthnAssertionError) -> Self {
+        match err {
+            WebAuthnAssertionError::CounterDidNotIncrease => Self::CounterDidNotIncrease,
+            WebAuthnAssertionError::InvalidPublicKey | WebAuthnAssertionError::InvalidSignature => {
+                Self::InvalidSignature
+            }
+        }
+    }
+}
+
+/// Persists the credential registered by `webauthn_enrollment_submit` and
+/// the running counter `verify_assertion` advances, so both survive across
+/// the later `webauthn_enrollment_accept` calls that check against them.
+///
+/// Mirrors the pluggable-trait extension-point pattern `AuthProvider` uses
+/// in `client_connection`: the actual backend (SQLite, in-memory, ...) lives
+/// outside this crate and is supplied by whoever drives the enrollment flow.
+#[async_trait]
+pub trait WebAuthnCredentialStore: Send + Sync {
+    type Error: std::error::Error;
+
+    /// Store (or overwrite) `credential`, keyed by its own `credential_id`.
+    async fn save_credential(&self, credential: &WebAuthnCredential) -> Result<(), Self::Error>;
+
+    /// Load back the credential previously saved under `credential_id`, so
+    /// its counter can be checked and advanced by `verify_assertion`.
+    async fn load_credential(
+        &self,
+        credential_id: &[u8],
+    ) -> Result<Option<WebAuthnCredential>, Self::Error>;
+}
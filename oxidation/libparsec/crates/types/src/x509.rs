@@ -0,0 +1,286 @@
+// Parsec Cloud (https://parsec.cloud) Copyright (c) BUSL-1.1 (eventually AGPL-3.0) 2016-present Scille SAS
+
+//! Verification of the DER X.509 certificates exchanged during PKI enrollment
+//! (`pki_enrollment_accept`/`pki_enrollment_reject`/`pki_enrollment_list`).
+//!
+//! Those commands only shuttle opaque certificate and payload-signature blobs,
+//! the actual trust decision is made here: parse the end-entity certificate,
+//! verify it is actually signed by a trusted issuer (not merely paired with
+//! one), reject weak signature algorithms and expired certificates, then use
+//! the extracted subject public key to verify the enrollment payload
+//! signature.
+
+use ring::signature::{self, UnparsedPublicKey, VerificationAlgorithm};
+use x509_parser::certificate::X509Certificate;
+use x509_parser::prelude::{FromDer, PublicKey, X509Error as ParserError};
+use x509_parser::signature_algorithm::SignatureAlgorithm as ParserSignatureAlgorithm;
+
+use crate::DateTime;
+
+/// Signature algorithms we are willing to trust for PKI enrollment certificates.
+///
+/// Kept as an explicit allow-list (rather than rejecting a block-list) so that
+/// adding a new legacy algorithm upstream never silently widens what we accept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllowedSignatureAlgorithm {
+    EcdsaP256Sha256,
+    EcdsaP384Sha384,
+    RsaPkcs1Sha256,
+    RsaPkcs1Sha384,
+    RsaPkcs1Sha512,
+    RsaPssSha256,
+}
+
+impl AllowedSignatureAlgorithm {
+    fn from_parser(algo: &ParserSignatureAlgorithm) -> Option<Self> {
+        match algo {
+            ParserSignatureAlgorithm::ECDSAWithSHA256 => Some(Self::EcdsaP256Sha256),
+            ParserSignatureAlgorithm::ECDSAWithSHA384 => Some(Self::EcdsaP384Sha384),
+            ParserSignatureAlgorithm::RSASSAPSS(params) => {
+                // Only accept the SHA256-based PSS parameter set, anything else
+                // (legacy SHA1-based PSS notably) is rejected.
+                params.is_sha256_based().then_some(Self::RsaPssSha256)
+            }
+            ParserSignatureAlgorithm::RSASignatureWithSHA256 => Some(Self::RsaPkcs1Sha256),
+            ParserSignatureAlgorithm::RSASignatureWithSHA384 => Some(Self::RsaPkcs1Sha384),
+            ParserSignatureAlgorithm::RSASignatureWithSHA512 => Some(Self::RsaPkcs1Sha512),
+            _ => None,
+        }
+    }
+
+    /// Recognize an algorithm from its raw dotted-decimal OID, as found
+    /// outside of a parsed certificate (e.g. an OCSP `BasicOCSPResponse`'s
+    /// `signatureAlgorithm`).
+    pub fn from_oid(oid: &str) -> Option<Self> {
+        match oid {
+            "1.2.840.10045.4.3.2" => Some(Self::EcdsaP256Sha256),
+            "1.2.840.10045.4.3.3" => Some(Self::EcdsaP384Sha384),
+            "1.2.840.113549.1.1.11" => Some(Self::RsaPkcs1Sha256),
+            "1.2.840.113549.1.1.12" => Some(Self::RsaPkcs1Sha384),
+            "1.2.840.113549.1.1.13" => Some(Self::RsaPkcs1Sha512),
+            // RSASSA-PSS carries its hash choice in the algorithm
+            // parameters rather than in the OID itself, which this
+            // string-only lookup can't inspect: left to the certificate
+            // parsing path (`from_parser`) where the full AlgorithmIdentifier
+            // is available.
+            _ => None,
+        }
+    }
+
+    /// The `ring` algorithm used both to check the cert's own signature
+    /// (against its issuer) and the enrollment payload signature (against
+    /// this cert's subject key).
+    pub fn verification_algorithm(self) -> &'static dyn VerificationAlgorithm {
+        match self {
+            Self::EcdsaP256Sha256 => &signature::ECDSA_P256_SHA256_ASN1,
+            Self::EcdsaP384Sha384 => &signature::ECDSA_P384_SHA384_ASN1,
+            Self::RsaPkcs1Sha256 => &signature::RSA_PKCS1_2048_8192_SHA256,
+            Self::RsaPkcs1Sha384 => &signature::RSA_PKCS1_2048_8192_SHA384,
+            Self::RsaPkcs1Sha512 => &signature::RSA_PKCS1_2048_8192_SHA512,
+            Self::RsaPssSha256 => &signature::RSA_PSS_2048_8192_SHA256,
+        }
+    }
+}
+
+/// Minimum/maximum RSA modulus size (in bits) we accept, mirroring the
+/// `RSA_PKCS1_2048_8192_SHA*` naming of the whitelist.
+const RSA_MIN_MODULUS_BITS: usize = 2048;
+const RSA_MAX_MODULUS_BITS: usize = 8192;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum CertificateVerificationError {
+    #[error("Certificate is not valid DER X.509")]
+    InvalidDer,
+    #[error("Certificate signature algorithm is not in the allowed whitelist")]
+    DisallowedSignatureAlgorithm,
+    #[error("Certificate RSA key size is out of the accepted 2048-8192 bits range")]
+    DisallowedKeySize,
+    #[error("Certificate has expired or is not yet valid")]
+    NotTimeValid,
+    #[error("Certificate is self-signed but absent from the trust store")]
+    UntrustedSelfSigned,
+    #[error("Certificate chain does not lead to a trusted root")]
+    UntrustedChain,
+    #[error("Certificate signature does not match its issuer's public key")]
+    InvalidCertificateSignature,
+    #[error("Payload signature does not match the certificate's public key")]
+    InvalidPayloadSignature,
+}
+
+/// Set of DER-encoded root certificates an organization trusts to issue
+/// enrollment certificates.
+#[derive(Debug, Clone, Default)]
+pub struct TrustAnchors {
+    roots_der: Vec<Vec<u8>>,
+}
+
+impl TrustAnchors {
+    pub fn new(roots_der: Vec<Vec<u8>>) -> Self {
+        Self { roots_der }
+    }
+
+    /// Return the trust anchor matching `der` byte-for-byte, if any. A
+    /// caller must still verify `der`'s signature over the candidate
+    /// certificate, byte-equality alone never implies trust.
+    fn find(&self, der: &[u8]) -> Option<&[u8]> {
+        self.roots_der
+            .iter()
+            .map(Vec::as_slice)
+            .find(|root| *root == der)
+    }
+}
+
+/// Result of a successful certificate verification, this is all the caller
+/// needs to validate a payload signature and to display who the enrollment
+/// is for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifiedEnrollmentCertificate {
+    pub subject_dn: String,
+    pub not_after: DateTime,
+    pub public_key_der: Vec<u8>,
+    pub algorithm: AllowedSignatureAlgorithm,
+}
+
+/// Return the RSA modulus bit length of `cert`'s public key, or `None` if it
+/// isn't an RSA key.
+fn rsa_modulus_bits(cert: &X509Certificate) -> Option<usize> {
+    match cert.public_key().parsed().ok()? {
+        PublicKey::RSA(rsa) => {
+            let modulus = rsa.modulus;
+            // Strip a possible leading `0x00` sign byte from the DER INTEGER
+            // encoding before counting bits.
+            let modulus = match modulus.first() {
+                Some(0) => &modulus[1..],
+                _ => modulus,
+            };
+            Some(modulus.len() * 8)
+        }
+        _ => None,
+    }
+}
+
+/// Verify that `der`'s signature was produced by `issuer_public_key_der`
+/// (the issuer's `SubjectPublicKeyInfo.subjectPublicKey`, as stored raw in
+/// `VerifiedEnrollmentCertificate::public_key_der`).
+fn verify_signed_by(
+    cert: &X509Certificate,
+    algo: AllowedSignatureAlgorithm,
+    issuer_public_key_der: &[u8],
+) -> Result<(), CertificateVerificationError> {
+    UnparsedPublicKey::new(algo.verification_algorithm(), issuer_public_key_der)
+        .verify(
+            cert.tbs_certificate.as_ref(),
+            cert.signature_value.as_ref(),
+        )
+        .map_err(|_| CertificateVerificationError::InvalidCertificateSignature)
+}
+
+/// Parse `der`, check it is signed with a whitelisted algorithm, that it is
+/// currently time-valid, and that it chains up to `trust_anchors`: the
+/// issuer's DER must be byte-equal to a trust anchor (or, if self-signed,
+/// `der` itself must be a trust anchor) *and* `der`'s signature must
+/// actually verify against that issuer's public key.
+///
+/// Note: this only verifies an end-entity certificate directly signed by one
+/// of `trust_anchors`' roots, there is no intermediate-CA path building here
+/// — `issuer_der` must itself be byte-equal to a trust anchor, not merely
+/// chain up to one through an intermediate. A deployment that issues
+/// enrollment certificates off an intermediate CA must enroll that
+/// intermediate itself as a trust anchor.
+pub fn verify_enrollment_certificate(
+    der: &[u8],
+    issuer_der: Option<&[u8]>,
+    trust_anchors: &TrustAnchors,
+    now: DateTime,
+) -> Result<VerifiedEnrollmentCertificate, CertificateVerificationError> {
+    let (_, cert) =
+        X509Certificate::from_der(der).map_err(|_: nom::Err<ParserError>| {
+            CertificateVerificationError::InvalidDer
+        })?;
+
+    let algo = cert
+        .signature_algorithm
+        .algorithm
+        .clone()
+        .try_into()
+        .ok()
+        .and_then(|parsed: ParserSignatureAlgorithm| AllowedSignatureAlgorithm::from_parser(&parsed))
+        .ok_or(CertificateVerificationError::DisallowedSignatureAlgorithm)?;
+
+    if matches!(
+        algo,
+        AllowedSignatureAlgorithm::RsaPkcs1Sha256
+            | AllowedSignatureAlgorithm::RsaPkcs1Sha384
+            | AllowedSignatureAlgorithm::RsaPkcs1Sha512
+            | AllowedSignatureAlgorithm::RsaPssSha256
+    ) {
+        let modulus_bits =
+            rsa_modulus_bits(&cert).ok_or(CertificateVerificationError::DisallowedKeySize)?;
+        if !(RSA_MIN_MODULUS_BITS..=RSA_MAX_MODULUS_BITS).contains(&modulus_bits) {
+            return Err(CertificateVerificationError::DisallowedKeySize);
+        }
+    }
+
+    let validity = cert.validity();
+    let now_ts = now.get_f64_with_us_precision() as i64;
+    if now_ts < validity.not_before.timestamp() || now_ts > validity.not_after.timestamp() {
+        return Err(CertificateVerificationError::NotTimeValid);
+    }
+
+    let is_self_signed = cert.issuer() == cert.subject();
+    let issuer_public_key_der = if is_self_signed {
+        trust_anchors
+            .find(der)
+            .ok_or(CertificateVerificationError::UntrustedSelfSigned)?;
+        // Self-signed: the certificate verifies against its own public key.
+        cert.public_key().subject_public_key.data.to_vec()
+    } else {
+        let issuer_der = issuer_der.ok_or(CertificateVerificationError::UntrustedChain)?;
+        trust_anchors
+            .find(issuer_der)
+            .ok_or(CertificateVerificationError::UntrustedChain)?;
+        let (_, issuer_cert) = X509Certificate::from_der(issuer_der)
+            .map_err(|_: nom::Err<ParserError>| CertificateVerificationError::UntrustedChain)?;
+
+        // Being in the trust store says nothing about whether the root
+        // itself is still within its own validity window, an expired trust
+        // anchor must not be able to vouch for a fresh certificate.
+        let issuer_validity = issuer_cert.validity();
+        if now_ts < issuer_validity.not_before.timestamp()
+            || now_ts > issuer_validity.not_after.timestamp()
+        {
+            return Err(CertificateVerificationError::NotTimeValid);
+        }
+
+        issuer_cert.public_key().subject_public_key.data.to_vec()
+    };
+
+    // Byte-equality against the trust store only tells us the issuer DER is
+    // known, it says nothing about whether `der` was actually issued by it:
+    // verify the certificate's own signature against that issuer's key.
+    verify_signed_by(&cert, algo, &issuer_public_key_der)?;
+
+    Ok(VerifiedEnrollmentCertificate {
+        subject_dn: cert.subject().to_string(),
+        not_after: DateTime::from_f64_with_us_precision(validity.not_after.timestamp() as f64),
+        public_key_der: cert.public_key().subject_public_key.data.to_vec(),
+        algorithm: algo,
+    })
+}
+
+/// Verify `signature` was produced over `payload` by the private key
+/// matching `verified.public_key_der`, using the same signature algorithm
+/// family as the certificate itself.
+///
+/// This is what `pki_enrollment_accept::Req::accept_payload_signature` /
+/// `submit_payload_signature` are checked with once the certificate chain
+/// has been established by `verify_enrollment_certificate`.
+pub fn verify_payload_signature(
+    verified: &VerifiedEnrollmentCertificate,
+    payload: &[u8],
+    signature: &[u8],
+) -> Result<(), CertificateVerificationError> {
+    UnparsedPublicKey::new(verified.algorithm.verification_algorithm(), &verified.public_key_der)
+        .verify(payload, signature)
+        .map_err(|_| CertificateVerificationError::InvalidPayloadSignature)
+}